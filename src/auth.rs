@@ -1,12 +1,20 @@
+use crate::environment::Environment;
+use crate::error::{body_excerpt, response_error, QuestradeError};
+use chrono::{DateTime, Duration, Utc};
+use http::StatusCode;
 use reqwest::header::{ACCEPT, CONTENT_LENGTH};
 use reqwest::Client;
-use serde::Deserialize;
-use std::error::Error;
-use std::ops::Add;
-use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+/// Access tokens expiring within this many seconds are considered expired, so that they aren't
+/// invalidated while a request is in flight.
+const EXPIRY_MARGIN_SECONDS: i64 = 60;
 
 /// Authentication token information.
-#[derive(Clone, PartialEq, Debug)]
+///
+/// The info can be serialized to restore a session after a restart, without consuming the
+/// refresh token while the access token is still valid.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct AuthenticationInfo {
     /// Token used to refresh access token.
     pub refresh_token: String,
@@ -15,74 +23,228 @@ pub struct AuthenticationInfo {
     pub access_token: String,
 
     /// Timestamp when access token expires.
-    pub expires_at: Instant,
+    pub expires_at: DateTime<Utc>,
 
     /// API server to connect to for queries.
     pub api_server: String,
 
-    /// Flag to indicate a practice account is in used.
-    pub is_demo: bool,
+    /// Environment the token was obtained from.
+    pub environment: Environment,
 }
 
 impl AuthenticationInfo {
-    /// Authenticates using the specified token and client
+    /// Authenticates against the specified environment using the token and client
     pub async fn authenticate(
         refresh_token: &str,
-        is_demo: bool,
+        environment: Environment,
         client: &Client,
-    ) -> Result<AuthenticationInfo, Box<dyn Error>> {
-        Self::refresh_access_token(refresh_token, is_demo, client).await
+    ) -> Result<AuthenticationInfo, QuestradeError> {
+        Self::refresh_access_token(refresh_token, environment, client).await
+    }
+
+    /// Obtains a new access token using the stored refresh token.
+    ///
+    /// Refresh tokens are single use, the returned info contains the new refresh token.
+    pub async fn refresh(&self, client: &Client) -> Result<AuthenticationInfo, QuestradeError> {
+        Self::refresh_access_token(
+            self.refresh_token.as_str(),
+            self.environment.clone(),
+            client,
+        )
+        .await
     }
 
-    async fn refresh(&self, client: &Client) -> Result<AuthenticationInfo, Box<dyn Error>> {
-        Self::refresh_access_token(self.refresh_token.as_str(), self.is_demo, client).await
+    /// Whether the access token has expired or is about to expire.
+    pub fn is_expired(&self) -> bool {
+        Utc::now() + Duration::seconds(EXPIRY_MARGIN_SECONDS) >= self.expires_at
     }
 
     async fn refresh_access_token(
         refresh_token: &str,
-        is_demo: bool,
+        environment: Environment,
         client: &Client,
-    ) -> Result<AuthenticationInfo, Box<dyn Error>> {
-        #[derive(Deserialize, Clone, PartialEq, Debug)]
-        pub struct AuthenticationInfoResponse {
-            pub refresh_token: String,
-            pub access_token: String,
-            pub expires_in: u64,
-            pub api_server: String,
-        }
+    ) -> Result<AuthenticationInfo, QuestradeError> {
+        Self::request_token(
+            &[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token),
+            ],
+            environment,
+            client,
+        )
+        .await
+    }
 
-        let url = get_url(is_demo);
+    /// Requests an access token from the token endpoint of the environment.
+    ///
+    /// A rejected grant is raised as `NotAuthenticatedError`, other failures (Eg: an outage of
+    /// the login server) are classified like API errors so they can be retried.
+    pub(crate) async fn request_token(
+        params: &[(&str, &str)],
+        environment: Environment,
+        client: &Client,
+    ) -> Result<AuthenticationInfo, QuestradeError> {
+        let url = environment.endpoints().token_url();
 
         let response = client
-            .post(url)
-            .query(&[
-                ("grant_type", "refresh_token"),
-                ("refresh_token", refresh_token),
-            ])
+            .post(&url)
+            .query(params)
             .header(CONTENT_LENGTH, 0)
             .header(ACCEPT, "application/json")
             .send()
-            .await?
-            .error_for_status()?
-            .json::<AuthenticationInfoResponse>()
             .await?;
 
-        Ok(AuthenticationInfo {
-            refresh_token: response.refresh_token,
-            access_token: response.access_token,
-            expires_at: Instant::now().add(Duration::from_secs(response.expires_in)),
-            api_server: response.api_server.trim_end_matches('/').into(),
-            is_demo,
-        })
+        let status = response.status();
+        if status == StatusCode::BAD_REQUEST || status == StatusCode::UNAUTHORIZED {
+            return Err(QuestradeError::NotAuthenticatedError(status));
+        }
+
+        if !status.is_success() {
+            return Err(response_error(&url, response).await);
+        }
+
+        let body = response.text().await?;
+        let response: AuthenticationInfoResponse =
+            serde_json::from_str(&body).map_err(|source| QuestradeError::DecodeError {
+                endpoint: url,
+                body: body_excerpt(&body),
+                source,
+            })?;
+
+        Ok(response.into_auth_info(environment))
+    }
+}
+
+/// Token response of the login server.
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct AuthenticationInfoResponse {
+    pub refresh_token: String,
+    pub access_token: String,
+    pub expires_in: i64,
+    pub api_server: String,
+}
+
+impl AuthenticationInfoResponse {
+    /// Converts the response into authentication info for the specified environment.
+    pub(crate) fn into_auth_info(self, environment: Environment) -> AuthenticationInfo {
+        AuthenticationInfo {
+            refresh_token: self.refresh_token,
+            access_token: self.access_token,
+            expires_at: Utc::now() + Duration::seconds(self.expires_in),
+            api_server: self.api_server.trim_end_matches('/').into(),
+            environment,
+        }
     }
 }
 
-/// Gets the authentication url
-#[inline]
-fn get_url(is_demo: bool) -> &'static str {
-    if is_demo {
-        "https://practicelogin.questrade.com/oauth2/token"
-    } else {
-        "https://login.questrade.com/oauth2/token"
+#[cfg(test)]
+mod tests {
+    use crate::auth::AuthenticationInfo;
+    use crate::environment::{Endpoints, Environment};
+    use crate::error::QuestradeError;
+    use chrono::{Duration, TimeZone, Utc};
+    use http::StatusCode;
+    use mockito::{mock, Matcher};
+    use reqwest::Client;
+    use serde_json::json;
+    use std::error::Error;
+
+    fn auth_info(expires_in: Duration) -> AuthenticationInfo {
+        AuthenticationInfo {
+            refresh_token: "refresh-token".to_string(),
+            access_token: "access-token".to_string(),
+            expires_at: Utc::now() + expires_in,
+            api_server: "https://api01.iq.questrade.com".to_string(),
+            environment: Environment::Live,
+        }
+    }
+
+    #[test]
+    fn is_expired() {
+        assert!(!auth_info(Duration::seconds(1800)).is_expired());
+        assert!(auth_info(Duration::seconds(30)).is_expired());
+        assert!(auth_info(Duration::seconds(0)).is_expired());
+    }
+
+    #[tokio::test]
+    async fn token_errors() {
+        let environment = Environment::Custom(Endpoints::new(mockito::server_url()));
+        let client = Client::new();
+        let authenticate = |token: &'static str| {
+            AuthenticationInfo::authenticate(token, environment.clone(), &client)
+        };
+
+        let _rejected = mock("POST", "/oauth2/token")
+            .match_query(Matcher::UrlEncoded(
+                "refresh_token".into(),
+                "rejected-token".into(),
+            ))
+            .with_status(400)
+            .create();
+        let _unavailable = mock("POST", "/oauth2/token")
+            .match_query(Matcher::UrlEncoded(
+                "refresh_token".into(),
+                "unavailable-token".into(),
+            ))
+            .with_status(503)
+            .with_body("Service Unavailable")
+            .create();
+        let _limited = mock("POST", "/oauth2/token")
+            .match_query(Matcher::UrlEncoded(
+                "refresh_token".into(),
+                "limited-token".into(),
+            ))
+            .with_status(429)
+            .create();
+
+        match authenticate("rejected-token").await {
+            Err(QuestradeError::NotAuthenticatedError(status)) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST)
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        match authenticate("unavailable-token").await {
+            Err(QuestradeError::StatusError { status, body, .. }) => {
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(body, "Service Unavailable");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        match authenticate("limited-token").await {
+            Err(QuestradeError::RateLimitError { .. }) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn serialization() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let auth_info = AuthenticationInfo {
+            refresh_token: "refresh-token".to_string(),
+            access_token: "access-token".to_string(),
+            expires_at: Utc.with_ymd_and_hms(2020, 11, 1, 15, 30, 0).unwrap(),
+            api_server: "http://localhost:1234".to_string(),
+            environment: Environment::Custom(Endpoints::new("http://localhost:1234")),
+        };
+
+        let value = serde_json::to_value(&auth_info)?;
+
+        assert_eq!(
+            value,
+            json!({
+                "refresh_token": "refresh-token",
+                "access_token": "access-token",
+                "expires_at": "2020-11-01T15:30:00Z",
+                "api_server": "http://localhost:1234",
+                "environment": { "Custom": { "login_server": "http://localhost:1234" } }
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<AuthenticationInfo>(value)?,
+            auth_info
+        );
+
+        Ok(())
     }
 }
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+/// Questrade environment to authenticate against.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub enum Environment {
+    /// Live accounts.
+    #[default]
+    Live,
+
+    /// Practice (demo) accounts.
+    Practice,
+
+    /// Custom endpoints, e.g. a local mock server.
+    Custom(Endpoints),
+}
+
+impl Environment {
+    /// Gets the endpoints of the environment.
+    pub fn endpoints(&self) -> Endpoints {
+        match self {
+            Environment::Live => Endpoints::new("https://login.questrade.com"),
+            Environment::Practice => Endpoints::new("https://practicelogin.questrade.com"),
+            Environment::Custom(endpoints) => endpoints.clone(),
+        }
+    }
+
+    /// Whether this is the practice environment.
+    pub fn is_demo(&self) -> bool {
+        *self == Environment::Practice
+    }
+}
+
+/// Server endpoints used for authentication.
+///
+/// API servers aren't configured here, they're assigned by the login server when authenticating.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Endpoints {
+    /// Base url of the login server. (Eg: https://login.questrade.com)
+    pub login_server: String,
+}
+
+impl Endpoints {
+    /// Creates endpoints for the specified login server.
+    pub fn new(login_server: impl Into<String>) -> Self {
+        Endpoints {
+            login_server: login_server.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Gets the url used to obtain access tokens.
+    pub fn token_url(&self) -> String {
+        format!("{}/oauth2/token", self.login_server)
+    }
+
+    /// Gets the url users are sent to when authorizing an application.
+    pub fn authorize_url(&self) -> String {
+        format!("{}/oauth2/authorize", self.login_server)
+    }
+}
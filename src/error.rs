@@ -1,9 +1,69 @@
+use crate::rate_limit::reset_at;
+use crate::{OrderId, OrderState};
+use chrono::{DateTime, Utc};
 use http::StatusCode;
+use reqwest::Response;
+use serde::Deserialize;
+use std::io;
 use thiserror::Error;
 
-/// API error types.
+/// Maximum number of characters of a response body kept in errors.
+const BODY_EXCERPT_LENGTH: usize = 256;
+
+/// Errors raised by the Questrade client.
+///
+/// The error is `Send + Sync`, so results can be moved between tasks.
 #[derive(Error, Debug)]
-pub enum ApiError {
+pub enum QuestradeError {
+    /// Raised when a request can't be sent or its response can't be read
+    #[error("Transport error: {0}")]
+    TransportError(#[from] reqwest::Error),
+
+    /// Raised when the API responds with an unsuccessful status
+    #[error("Request to {endpoint} failed with status {status}: {body}")]
+    StatusError {
+        endpoint: String,
+        status: StatusCode,
+        body: String,
+    },
+
+    /// Raised when the API responds with an error body
+    #[error("Request to {endpoint} failed with code {code}: {message}")]
+    ApiError {
+        endpoint: String,
+        status: StatusCode,
+        code: u32,
+        message: String,
+        kind: ApiErrorKind,
+    },
+
+    /// Raised when an order can't be canceled or replaced because of its state (Eg: it was
+    /// already filled)
+    #[error("Order {order_id} cannot be modified in state {state:?}")]
+    OrderStateError {
+        order_id: OrderId,
+        state: OrderState,
+    },
+
+    /// Raised when a API call is made and the user isn't authenticated
+    #[error("Not authenticated")]
+    NotAuthenticatedError(StatusCode),
+
+    /// Raised when a response body cannot be decoded
+    #[error("Cannot decode response of {endpoint}: {source}. Body: {body}")]
+    DecodeError {
+        endpoint: String,
+        body: String,
+        source: serde_json::Error,
+    },
+
+    /// Raised when the API rejects a request because the rate limit was exceeded
+    #[error("Rate limit exceeded for {endpoint}")]
+    RateLimitError {
+        endpoint: String,
+        reset_at: Option<DateTime<Utc>>,
+    },
+
     /// Raised when a response is missing a field
     #[error("Missing field in json response: {0}")]
     MissingFieldError(&'static str),
@@ -12,7 +72,170 @@ pub enum ApiError {
     #[error("Cannot convert field {0} to type {1}")]
     InvalidTypeError(&'static str, &'static str),
 
-    /// Raised when a API call is made and the user isn't authenticated
-    #[error("Not authenticated")]
-    NotAuthenticatedError(StatusCode),
+    /// Raised when authenticating from a token store that holds no authentication info
+    #[error("No authentication info in token store")]
+    MissingTokenError,
+
+    /// Raised when reading or writing local resources fails (Eg: the token store)
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+
+    /// Raised when the interactive login flow fails
+    #[error("Login failed: {0}")]
+    LoginError(String),
+}
+
+/// Reason of an error reported by the API.
+///
+/// Rate limited responses are raised as `QuestradeError::RateLimitError` instead.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ApiErrorKind {
+    /// Invalid, missing or too long argument.
+    Argument,
+
+    /// Access token is missing, invalid or not allowed to access the resource.
+    Auth,
+
+    /// Requested resource doesn't exist.
+    NotFound,
+
+    /// Order was rejected.
+    OrderRejected,
+
+    /// Any other reason.
+    Other,
+}
+
+impl ApiErrorKind {
+    /// Classifies an error from its code and the status of the response.
+    fn classify(status: StatusCode, code: u32) -> Self {
+        match (code, status) {
+            (1002..=1004, _) | (_, StatusCode::BAD_REQUEST) => ApiErrorKind::Argument,
+            (1017, _) | (_, StatusCode::UNAUTHORIZED) | (_, StatusCode::FORBIDDEN) => {
+                ApiErrorKind::Auth
+            }
+            (_, StatusCode::NOT_FOUND) => ApiErrorKind::NotFound,
+            _ => ApiErrorKind::Other,
+        }
+    }
+}
+
+/// Error body returned by the API.
+#[derive(Deserialize, Debug)]
+struct ErrorResponse {
+    code: u32,
+    message: String,
+
+    /// Set when the error relates to an order.
+    #[serde(rename = "orderId")]
+    order_id: Option<u64>,
+}
+
+/// Converts an unsuccessful response into an error.
+///
+/// Rate limited responses are raised as `RateLimitError`, other bodies are decoded by
+/// `status_error`.
+pub(crate) async fn response_error(endpoint: &str, response: Response) -> QuestradeError {
+    let status = response.status();
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return QuestradeError::RateLimitError {
+            endpoint: endpoint.to_string(),
+            reset_at: reset_at(response.headers()),
+        };
+    }
+
+    match response.text().await {
+        Ok(body) => status_error(endpoint, status, &body),
+        Err(e) => e.into(),
+    }
+}
+
+/// Converts an unsuccessful response into an error, decoding the error body when possible.
+pub(crate) fn status_error(endpoint: &str, status: StatusCode, body: &str) -> QuestradeError {
+    match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => QuestradeError::ApiError {
+            endpoint: endpoint.to_string(),
+            status,
+            code: response.code,
+            kind: match response.order_id {
+                Some(_) => ApiErrorKind::OrderRejected,
+                None => ApiErrorKind::classify(status, response.code),
+            },
+            message: response.message,
+        },
+        Err(_) if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+            QuestradeError::NotAuthenticatedError(status)
+        }
+        Err(_) => QuestradeError::StatusError {
+            endpoint: endpoint.to_string(),
+            status,
+            body: body_excerpt(body),
+        },
+    }
+}
+
+/// Truncates a response body so it can be kept in an error.
+pub(crate) fn body_excerpt(body: &str) -> String {
+    body.chars().take(BODY_EXCERPT_LENGTH).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{status_error, ApiErrorKind, QuestradeError};
+    use http::StatusCode;
+
+    fn kind(status: StatusCode, body: &str) -> Option<ApiErrorKind> {
+        match status_error("accounts", status, body) {
+            QuestradeError::ApiError { kind, .. } => Some(kind),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn classify() {
+        let invalid = r#"{"code": 1002, "message": "Invalid or malformed argument"}"#;
+        let token = r#"{"code": 1017, "message": "Access token is invalid"}"#;
+        let missing = r#"{"code": 1001, "message": "Invalid endpoint"}"#;
+        let rejected = r#"{"code": 1019, "message": "Order rejected", "orderId": 173577870}"#;
+
+        assert_eq!(
+            kind(StatusCode::BAD_REQUEST, invalid),
+            Some(ApiErrorKind::Argument)
+        );
+        assert_eq!(
+            kind(StatusCode::UNAUTHORIZED, token),
+            Some(ApiErrorKind::Auth)
+        );
+        assert_eq!(
+            kind(StatusCode::NOT_FOUND, missing),
+            Some(ApiErrorKind::NotFound)
+        );
+        assert_eq!(
+            kind(StatusCode::BAD_REQUEST, rejected),
+            Some(ApiErrorKind::OrderRejected)
+        );
+        assert_eq!(
+            kind(StatusCode::BAD_GATEWAY, missing),
+            Some(ApiErrorKind::Other)
+        );
+    }
+
+    #[test]
+    fn unstructured_body() {
+        match status_error("accounts", StatusCode::FORBIDDEN, "") {
+            QuestradeError::NotAuthenticatedError(status) => {
+                assert_eq!(status, StatusCode::FORBIDDEN)
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+
+        match status_error("accounts", StatusCode::BAD_GATEWAY, "<html></html>") {
+            QuestradeError::StatusError { status, body, .. } => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(body, "<html></html>");
+            }
+            error => panic!("unexpected error: {}", error),
+        }
+    }
 }
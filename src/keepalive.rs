@@ -0,0 +1,212 @@
+use crate::error::QuestradeError;
+use crate::random::random_duration;
+use crate::Questrade;
+use std::cmp::max;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::delay_for;
+
+/// Shortest interval between refreshes, so a misconfigured schedule can't flood the login server.
+const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Schedule of the keepalive task.
+///
+/// Questrade refresh tokens expire when unused for about a week, refreshing on a shorter interval
+/// keeps the token chain alive.
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeepaliveConfig {
+    interval: Duration,
+    jitter: Duration,
+}
+
+impl KeepaliveConfig {
+    /// Creates a schedule refreshing on the specified interval, without jitter.
+    ///
+    /// Intervals shorter than a minute are raised to a minute.
+    pub fn new(interval: Duration) -> Self {
+        KeepaliveConfig {
+            interval: max(interval, MIN_INTERVAL),
+            jitter: Duration::from_secs(0),
+        }
+    }
+
+    /// Sets the maximum random delay added to every interval.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay before the next refresh.
+    fn next_delay(&self) -> Duration {
+        self.interval + random_duration(self.jitter)
+    }
+}
+
+impl Default for KeepaliveConfig {
+    /// Refreshes daily, with up to an hour of jitter.
+    fn default() -> Self {
+        KeepaliveConfig::new(Duration::from_secs(24 * 3600)).with_jitter(Duration::from_secs(3600))
+    }
+}
+
+/// Background task periodically refreshing the tokens of a client.
+///
+/// The task runs until stopped or dropped.
+pub struct Keepalive {
+    shutdown: Option<oneshot::Sender<()>>,
+    handle: JoinHandle<()>,
+}
+
+impl Keepalive {
+    /// Spawns a keepalive task for the client on the current runtime.
+    ///
+    /// Every failed refresh is passed to `on_failure`; the task keeps running afterwards.
+    pub fn spawn<F>(api: Arc<Questrade>, config: KeepaliveConfig, on_failure: F) -> Self
+    where
+        F: Fn(&QuestradeError) + Send + Sync + 'static,
+    {
+        let (shutdown, mut stopped) = oneshot::channel();
+
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = delay_for(config.next_delay()) => {}
+                    _ = &mut stopped => break,
+                }
+
+                if let Err(e) = api.refresh().await {
+                    on_failure(&e);
+                }
+            }
+        });
+
+        Keepalive {
+            shutdown: Some(shutdown),
+            handle,
+        }
+    }
+
+    /// Stops the task, waiting for a refresh in progress to complete.
+    pub async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        let _ = (&mut self.handle).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keepalive::{Keepalive, KeepaliveConfig, MIN_INTERVAL};
+    use crate::{AuthenticationInfo, Endpoints, Environment, Questrade};
+    use chrono::Utc;
+    use mockito::{mock, Matcher};
+    use reqwest::Client;
+    use serde_json::json;
+    use std::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn get_api(refresh_token: &str) -> Arc<Questrade> {
+        let auth_info = AuthenticationInfo {
+            access_token: "access-token".to_string(),
+            api_server: mockito::server_url(),
+            refresh_token: refresh_token.to_string(),
+            expires_at: Utc::now() + chrono::Duration::seconds(1800),
+            environment: Environment::Custom(Endpoints::new(mockito::server_url())),
+        };
+
+        Arc::new(Questrade::with_authentication(auth_info, Client::new()))
+    }
+
+    /// Builds a schedule with an interval below the minimum, to keep the tests fast.
+    fn fast_config(interval: Duration, jitter: Duration) -> KeepaliveConfig {
+        KeepaliveConfig { interval, jitter }
+    }
+
+    #[test]
+    fn minimum_interval() {
+        assert_eq!(
+            KeepaliveConfig::new(Duration::from_secs(0)),
+            KeepaliveConfig::new(MIN_INTERVAL)
+        );
+        assert_eq!(
+            KeepaliveConfig::new(Duration::from_secs(3600)).next_delay(),
+            Duration::from_secs(3600)
+        );
+    }
+
+    #[tokio::test]
+    async fn refreshes_periodically() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _m = mock("POST", "/oauth2/token")
+            .match_query(Matcher::UrlEncoded(
+                "refresh_token".into(),
+                "keepalive-token".into(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "access_token": "keepalive-access-token",
+                    "token_type": "Bearer",
+                    "expires_in": 1800,
+                    "refresh_token": "keepalive-token",
+                    "api_server": mockito::server_url(),
+                })
+                .to_string(),
+            )
+            .expect_at_least(2)
+            .create();
+
+        let api = get_api("keepalive-token");
+        let keepalive = Keepalive::spawn(
+            api.clone(),
+            fast_config(Duration::from_millis(10), Duration::from_millis(5)),
+            |e| panic!("refresh failed: {}", e),
+        );
+
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        keepalive.stop().await;
+
+        _m.assert();
+        assert_eq!(
+            api.get_auth_info().map(|auth_info| auth_info.access_token),
+            Some("keepalive-access-token".to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reports_failures() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let _m = mock("POST", "/oauth2/token")
+            .match_query(Matcher::UrlEncoded(
+                "refresh_token".into(),
+                "revoked-keepalive-token".into(),
+            ))
+            .with_status(400)
+            .create();
+
+        let failures = Arc::new(AtomicUsize::new(0));
+        let counter = failures.clone();
+
+        let keepalive = Keepalive::spawn(
+            get_api("revoked-keepalive-token"),
+            fast_config(Duration::from_millis(10), Duration::from_secs(0)),
+            move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            },
+        );
+
+        tokio::time::delay_for(Duration::from_millis(200)).await;
+        keepalive.stop().await;
+
+        assert!(failures.load(Ordering::SeqCst) >= 2);
+
+        Ok(())
+    }
+}
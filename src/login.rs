@@ -0,0 +1,559 @@
+use crate::auth::{AuthenticationInfo, AuthenticationInfoResponse};
+use crate::environment::Environment;
+use crate::error::QuestradeError;
+use crate::random::random_token;
+use reqwest::{Client, Url};
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// Maximum size of a redirect request accepted by the listener.
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// How long a single connection may take to send its request.
+///
+/// Browsers open speculative connections that never send anything, they mustn't hold up the
+/// actual redirect.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Page served for implicit grants, forwarding the url fragment (which browsers don't send to the
+/// server) back to the listener as a query string.
+const FRAGMENT_FORWARD_PAGE: &str = "<html><body><script>\
+     if (window.location.hash.length > 1) {\
+     window.location.replace(window.location.pathname + '?' + window.location.hash.substring(1));\
+     } else {\
+     document.body.textContent = 'Login failed, no authorization response was received.';\
+     }\
+     </script></body></html>";
+
+/// Page served once the redirect has been received.
+const COMPLETED_PAGE: &str = "<html><body>Login complete, you may close this window.</body></html>";
+
+/// OAuth2 grant requested from the authorization server.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResponseType {
+    /// Authorization code grant, exchanged for tokens by the listener.
+    Code,
+
+    /// Implicit grant, the tokens are returned in the redirect url fragment.
+    Token,
+}
+
+/// First-time login using the OAuth2 authorization flow.
+///
+/// The user is sent to the url returned by `authorize_url`. Once they allow access, Questrade
+/// redirects them to the redirect uri registered with the consumer key, where `listen` picks up
+/// the response. The redirect uri must point to the local machine (Eg: http://localhost:8080/).
+///
+/// Every flow sends a random `state` with the authorization request, redirects that don't carry
+/// it back are rejected.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LoginFlow {
+    consumer_key: String,
+    redirect_uri: Url,
+    authorize_endpoint: Url,
+    state: String,
+    environment: Environment,
+    response_type: ResponseType,
+    timeout: Duration,
+}
+
+impl LoginFlow {
+    /// Creates a login flow for an application registered with the specified consumer key and
+    /// redirect uri, using the authorization code grant.
+    pub fn new(
+        consumer_key: &str,
+        redirect_uri: &str,
+        environment: Environment,
+    ) -> Result<Self, QuestradeError> {
+        let redirect_uri = Url::parse(redirect_uri).map_err(login_error)?;
+
+        if redirect_uri.scheme() != "http" || !is_loopback(&redirect_uri) {
+            return Err(QuestradeError::LoginError(format!(
+                "redirect uri must be a local http url. Got: {}",
+                redirect_uri
+            )));
+        }
+
+        let authorize_endpoint =
+            Url::parse(&environment.endpoints().authorize_url()).map_err(login_error)?;
+
+        Ok(LoginFlow {
+            consumer_key: consumer_key.to_string(),
+            redirect_uri,
+            authorize_endpoint,
+            state: random_token().map_err(login_error)?,
+            environment,
+            response_type: ResponseType::Code,
+            timeout: Duration::from_secs(300),
+        })
+    }
+
+    /// Sets the grant requested from the authorization server.
+    pub fn with_response_type(mut self, response_type: ResponseType) -> Self {
+        self.response_type = response_type;
+        self
+    }
+
+    /// Sets how long to wait for the user to complete the login. Defaults to 5 minutes.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Gets the state sent with the authorization request, which the redirect must carry back.
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Gets the url the user should be sent to in order to authorize the application.
+    pub fn authorize_url(&self) -> Url {
+        let response_type = match self.response_type {
+            ResponseType::Code => "code",
+            ResponseType::Token => "token",
+        };
+
+        let mut url = self.authorize_endpoint.clone();
+
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.consumer_key)
+            .append_pair("response_type", response_type)
+            .append_pair("redirect_uri", self.redirect_uri.as_str())
+            .append_pair("state", &self.state);
+
+        url
+    }
+
+    /// Listens on the redirect uri for the authorization response and converts it to
+    /// authentication info.
+    ///
+    /// Authorization codes are exchanged for tokens using the specified client.
+    pub async fn listen(&self, client: &Client) -> Result<AuthenticationInfo, QuestradeError> {
+        let params = timeout(self.timeout, self.receive_redirect())
+            .await
+            .map_err(|_| {
+                QuestradeError::LoginError("timed out waiting for redirect".to_string())
+            })??;
+
+        if let Some(error) = params.get("error") {
+            return Err(QuestradeError::LoginError(error.clone()));
+        }
+
+        match self.response_type {
+            ResponseType::Code => match params.get("code") {
+                Some(code) => self.exchange_code(code, client).await,
+                None => Err(QuestradeError::LoginError(
+                    "redirect is missing the authorization code".to_string(),
+                )),
+            },
+            ResponseType::Token => self.parse_params(params),
+        }
+    }
+
+    /// Exchanges an authorization code for authentication info.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        client: &Client,
+    ) -> Result<AuthenticationInfo, QuestradeError> {
+        AuthenticationInfo::request_token(
+            &[
+                ("client_id", self.consumer_key.as_str()),
+                ("code", code),
+                ("grant_type", "authorization_code"),
+                ("redirect_uri", self.redirect_uri.as_str()),
+            ],
+            self.environment.clone(),
+            client,
+        )
+        .await
+    }
+
+    /// Parses the url fragment of an implicit grant redirect into authentication info.
+    ///
+    /// The fragment must carry the state of this flow.
+    pub fn parse_fragment(&self, fragment: &str) -> Result<AuthenticationInfo, QuestradeError> {
+        let mut url = self.redirect_uri.clone();
+        url.set_query(Some(fragment.trim_start_matches('#')));
+
+        let params = url.query_pairs().into_owned().collect();
+        self.check_state(&params)?;
+
+        self.parse_params(params)
+    }
+
+    /// Checks that the parameters of a redirect carry the state of this flow.
+    fn check_state(&self, params: &HashMap<String, String>) -> Result<(), QuestradeError> {
+        match params.get("state") {
+            Some(state) if *state == self.state => Ok(()),
+            _ => Err(QuestradeError::LoginError(
+                "redirect state doesn't match the login flow".to_string(),
+            )),
+        }
+    }
+
+    /// Converts the parameters of an implicit grant into authentication info.
+    fn parse_params(
+        &self,
+        mut params: HashMap<String, String>,
+    ) -> Result<AuthenticationInfo, QuestradeError> {
+        let mut take = |name: &'static str| {
+            params
+                .remove(name)
+                .ok_or(QuestradeError::MissingFieldError(name))
+        };
+
+        let response = AuthenticationInfoResponse {
+            refresh_token: take("refresh_token")?,
+            access_token: take("access_token")?,
+            expires_in: take("expires_in")?
+                .parse()
+                .map_err(|_| QuestradeError::InvalidTypeError("expires_in", "i64"))?,
+            api_server: take("api_server")?,
+        };
+
+        Ok(response.into_auth_info(self.environment.clone()))
+    }
+
+    /// Accepts connections on the redirect uri until one carries the authorization response.
+    ///
+    /// Every connection is handled in its own task with its own timeout, failed connections are
+    /// dropped without ending the login.
+    async fn receive_redirect(&self) -> Result<HashMap<String, String>, QuestradeError> {
+        let address = format!(
+            "{}:{}",
+            self.redirect_uri.host_str().unwrap_or("localhost"),
+            self.redirect_uri.port_or_known_default().unwrap_or(80)
+        );
+
+        let mut listener = TcpListener::bind(address).await?;
+        let (sender, mut receiver) = mpsc::channel(1);
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let stream = match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(_) => continue,
+                    };
+
+                    let flow = self.clone();
+                    let mut sender = sender.clone();
+
+                    tokio::spawn(async move {
+                        let handled = timeout(CONNECTION_TIMEOUT, flow.handle_connection(stream));
+
+                        if let Ok(Ok(Some(params))) = handled.await {
+                            let _ = sender.send(params).await;
+                        }
+                    });
+                }
+                Some(params) = receiver.recv() => return Ok(params),
+            }
+        }
+    }
+
+    /// Answers a connection to the listener, returning the authorization response it carries (if
+    /// any).
+    async fn handle_connection(
+        &self,
+        mut stream: TcpStream,
+    ) -> io::Result<Option<HashMap<String, String>>> {
+        let url = match read_request_target(&mut stream)
+            .await
+            .and_then(|target| self.redirect_uri.join(&target).ok())
+        {
+            Some(url) => url,
+            None => {
+                respond(&mut stream, "400 Bad Request", "").await?;
+                return Ok(None);
+            }
+        };
+
+        if url.path() != self.redirect_uri.path() {
+            respond(&mut stream, "404 Not Found", "").await?;
+            return Ok(None);
+        }
+
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+
+        if params.is_empty() && self.response_type == ResponseType::Token {
+            respond(&mut stream, "200 OK", FRAGMENT_FORWARD_PAGE).await?;
+            return Ok(None);
+        }
+
+        if self.check_state(&params).is_err() {
+            respond(&mut stream, "400 Bad Request", "").await?;
+            return Ok(None);
+        }
+
+        respond(&mut stream, "200 OK", COMPLETED_PAGE).await?;
+
+        Ok(Some(params))
+    }
+}
+
+/// Whether the url points to the local machine.
+fn is_loopback(url: &Url) -> bool {
+    match url.host_str() {
+        Some("localhost") => true,
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    }
+}
+
+/// Reads the head of an HTTP request, returning the request target (path and query).
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok()?;
+
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return None;
+        }
+
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next()?.split_whitespace();
+
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+/// Converts an invalid url into a login error.
+fn login_error(e: impl std::fmt::Display) -> QuestradeError {
+    QuestradeError::LoginError(e.to_string())
+}
+
+/// Writes a minimal HTML response and closes the connection.
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::{Endpoints, Environment};
+    use crate::login::{LoginFlow, ResponseType};
+    use mockito::{mock, Matcher};
+    use reqwest::Client;
+    use serde_json::json;
+    use std::error::Error;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    fn mock_environment() -> Environment {
+        Environment::Custom(Endpoints::new(mockito::server_url()))
+    }
+
+    /// Finds a free port on the loopback interface.
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .map(|address| address.port())
+            .expect("no free port")
+    }
+
+    /// Sends a redirect to the listener, retrying until it's bound.
+    async fn redirect(url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        for _ in 0..50 {
+            match Client::new().get(url).send().await {
+                Ok(response) => return Ok(response.text().await?),
+                Err(_) => tokio::time::delay_for(Duration::from_millis(20)).await,
+            }
+        }
+
+        Err("listener never started".into())
+    }
+
+    #[test]
+    fn authorize_url() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let flow = LoginFlow::new(
+            "consumer-key",
+            "http://localhost:8080/callback",
+            Environment::Live,
+        )?;
+
+        assert_eq!(
+            flow.authorize_url().as_str(),
+            format!(
+                "https://login.questrade.com/oauth2/authorize?client_id=consumer-key\
+                 &response_type=code&redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcallback\
+                 &state={}",
+                flow.state()
+            )
+        );
+        assert_eq!(flow.state().len(), 32);
+
+        let other = LoginFlow::new(
+            "consumer-key",
+            "http://localhost:8080/callback",
+            Environment::Live,
+        )?;
+        assert_ne!(flow.state(), other.state());
+
+        let flow = flow.with_response_type(ResponseType::Token);
+        assert!(flow
+            .authorize_url()
+            .as_str()
+            .contains("response_type=token"));
+
+        assert!(LoginFlow::new("consumer-key", "https://example.com/", Environment::Live).is_err());
+        assert!(LoginFlow::new("consumer-key", "http://example.com/", Environment::Live).is_err());
+        assert!(
+            LoginFlow::new("consumer-key", "http://127.0.0.1:8080/", Environment::Live).is_ok()
+        );
+        assert!(LoginFlow::new("consumer-key", "http://[::1]:8080/", Environment::Live).is_ok());
+
+        let malformed = Environment::Custom(Endpoints::new("not a url"));
+        assert!(LoginFlow::new("consumer-key", "http://localhost:8080/", malformed).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_fragment() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let flow = LoginFlow::new("key", "http://localhost:8080/", Environment::Practice)?;
+
+        let fragment = "#access_token=C3lTUKuNQrAAmSD%2FOPEn\
+             &refresh_token=aSBe7wAAdx88QTbwut0tiu3SYic3ox8F&token_type=Bearer&expires_in=1800\
+             &api_server=https%3A%2F%2Fapi01.iq.questrade.com%2F";
+
+        let auth_info = flow.parse_fragment(&format!("{}&state={}", fragment, flow.state()))?;
+
+        assert_eq!(auth_info.access_token, "C3lTUKuNQrAAmSD/OPEn");
+        assert_eq!(auth_info.refresh_token, "aSBe7wAAdx88QTbwut0tiu3SYic3ox8F");
+        assert_eq!(auth_info.api_server, "https://api01.iq.questrade.com");
+        assert_eq!(auth_info.environment, Environment::Practice);
+        assert!(!auth_info.is_expired());
+
+        assert!(flow.parse_fragment("access_token=abc").is_err());
+
+        // Tokens without the state of the flow (Eg: injected by another page) are rejected.
+        assert!(flow.parse_fragment(fragment).is_err());
+        assert!(flow
+            .parse_fragment(&format!("{}&state=forged-state", fragment))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn listen_for_code() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let port = free_port();
+        let redirect_uri = format!("http://127.0.0.1:{}/callback", port);
+
+        let _m = mock("POST", "/oauth2/token")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".into(), "authorization_code".into()),
+                Matcher::UrlEncoded("client_id".into(), "consumer-key".into()),
+                Matcher::UrlEncoded("code".into(), "authorization-code".into()),
+                Matcher::UrlEncoded("redirect_uri".into(), redirect_uri.clone()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "access_token": "access-token",
+                    "token_type": "Bearer",
+                    "expires_in": 1800,
+                    "refresh_token": "refresh-token",
+                    "api_server": "https://api01.iq.questrade.com/",
+                })
+                .to_string(),
+            )
+            .create();
+
+        let flow = LoginFlow::new("consumer-key", &redirect_uri, mock_environment())?;
+        let state = flow.state().to_string();
+        let login = tokio::spawn(async move { flow.listen(&Client::new()).await });
+
+        // A connection that never sends a request doesn't hold up the redirect.
+        let _idle = loop {
+            match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::delay_for(Duration::from_millis(20)).await,
+            }
+        };
+
+        redirect(&format!("http://127.0.0.1:{}/other", port)).await?;
+        redirect(&format!(
+            "{}?code=injected-code&state=forged-state",
+            redirect_uri
+        ))
+        .await?;
+        redirect(&format!(
+            "{}?code=authorization-code&state={}",
+            redirect_uri, state
+        ))
+        .await?;
+
+        let auth_info = login.await??;
+        assert_eq!(auth_info.access_token, "access-token");
+        assert_eq!(auth_info.refresh_token, "refresh-token");
+        assert_eq!(auth_info.environment, mock_environment());
+
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn listen_for_token() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let port = free_port();
+        let redirect_uri = format!("http://127.0.0.1:{}/", port);
+
+        let flow = LoginFlow::new("consumer-key", &redirect_uri, Environment::Live)?
+            .with_response_type(ResponseType::Token);
+        let state = flow.state().to_string();
+        let login = tokio::spawn(async move { flow.listen(&Client::new()).await });
+
+        assert!(redirect(&redirect_uri).await?.contains("location.hash"));
+        redirect(&format!(
+            "{}?access_token=access-token&refresh_token=refresh-token&token_type=Bearer\
+             &expires_in=1800&api_server=https%3A%2F%2Fapi01.iq.questrade.com%2F&state={}",
+            redirect_uri, state
+        ))
+        .await?;
+
+        let auth_info = login.await??;
+        assert_eq!(auth_info.access_token, "access-token");
+        assert_eq!(auth_info.api_server, "https://api01.iq.questrade.com");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn listen_times_out() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let redirect_uri = format!("http://127.0.0.1:{}/", free_port());
+
+        let result = LoginFlow::new("consumer-key", &redirect_uri, Environment::Live)?
+            .with_timeout(Duration::from_millis(50))
+            .listen(&Client::new())
+            .await;
+
+        assert!(result.is_err());
+
+        Ok(())
+    }
+}
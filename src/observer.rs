@@ -0,0 +1,71 @@
+use crate::auth::AuthenticationInfo;
+use crate::error::QuestradeError;
+use std::io;
+use std::sync::Arc;
+
+/// Authentication event reported to observers.
+#[derive(Debug)]
+pub enum AuthEvent<'a> {
+    /// A new access token was obtained, either by authenticating or refreshing.
+    Refreshed {
+        /// Info that was replaced (if any).
+        old: Option<&'a AuthenticationInfo>,
+
+        /// Newly obtained info.
+        new: &'a AuthenticationInfo,
+    },
+
+    /// Obtaining a new access token failed.
+    RefreshFailed {
+        /// Info that was being refreshed (if any).
+        old: Option<&'a AuthenticationInfo>,
+
+        /// Reason of the failure.
+        reason: &'a QuestradeError,
+    },
+
+    /// A new refresh token couldn't be saved to the token store.
+    ///
+    /// The previous refresh token has already been consumed, so the session can't be restored
+    /// from the store after a restart.
+    SaveFailed {
+        /// Info that wasn't saved.
+        auth_info: &'a AuthenticationInfo,
+
+        /// Reason of the failure.
+        reason: &'a io::Error,
+    },
+}
+
+impl AuthEvent<'_> {
+    /// Whether a refresh moved the user to a different API server.
+    pub fn api_server_changed(&self) -> bool {
+        match self {
+            AuthEvent::Refreshed {
+                old: Some(old),
+                new,
+            } => old.api_server != new.api_server,
+            _ => false,
+        }
+    }
+}
+
+/// Observer notified of authentication events, e.g. to monitor token rotation.
+///
+/// Implemented for closures taking an `&AuthEvent`.
+pub trait AuthObserver: Send + Sync {
+    /// Called for every authentication event.
+    fn notify(&self, event: &AuthEvent);
+}
+
+impl<F: Fn(&AuthEvent) + Send + Sync> AuthObserver for F {
+    fn notify(&self, event: &AuthEvent) {
+        self(event)
+    }
+}
+
+impl<O: AuthObserver + ?Sized> AuthObserver for Arc<O> {
+    fn notify(&self, event: &AuthEvent) {
+        (**self).notify(event)
+    }
+}
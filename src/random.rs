@@ -0,0 +1,66 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Gets a random number.
+///
+/// RandomState is seeded randomly, which is good enough for jitter but not for secrets, see
+/// `random_token`.
+pub(crate) fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Gets a random hex string of 128 bits from the operating system's secure random source.
+pub(crate) fn random_token() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes)?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Gets a random duration between zero and `max`.
+pub(crate) fn random_duration(max: Duration) -> Duration {
+    let max_nanos = max.as_nanos() as u64;
+
+    if max_nanos == 0 {
+        return max;
+    }
+
+    Duration::from_nanos(random_u64() % max_nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::random::{random_duration, random_token, random_u64};
+    use std::time::Duration;
+
+    #[test]
+    fn random_duration_within_bounds() {
+        let max = Duration::from_millis(10);
+
+        for _ in 0..100 {
+            assert!(random_duration(max) < max);
+        }
+
+        assert_eq!(
+            random_duration(Duration::from_secs(0)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn random_u64_varies() {
+        assert_ne!(random_u64(), random_u64());
+    }
+
+    #[test]
+    fn random_token_varies() -> Result<(), getrandom::Error> {
+        let token = random_token()?;
+
+        assert_eq!(token.len(), 32);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, random_token()?);
+
+        Ok(())
+    }
+}
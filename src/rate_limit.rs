@@ -0,0 +1,208 @@
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Header holding the number of requests left before the limit is reached.
+const REMAINING_HEADER: &str = "X-RateLimit-Remaining";
+
+/// Header holding the time at which the limit resets, as a unix timestamp.
+const RESET_HEADER: &str = "X-RateLimit-Reset";
+
+/// How long other requests wait for the first request after a reset to report the new budget.
+const PROBE_TIMEOUT_MILLIS: i64 = 1000;
+
+/// Category of API calls sharing a rate limit.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RateLimitCategory {
+    /// Account calls (Eg: accounts, positions, orders, server time).
+    Account,
+
+    /// Market data calls (Eg: quotes, symbols, candles).
+    MarketData,
+}
+
+impl RateLimitCategory {
+    /// Category of the endpoint at the specified url suffix.
+    pub(crate) fn of(url_suffix: &str) -> Self {
+        if url_suffix.starts_with("markets") || url_suffix.starts_with("symbols") {
+            RateLimitCategory::MarketData
+        } else {
+            RateLimitCategory::Account
+        }
+    }
+}
+
+/// Remaining request budget of a category, as last reported by the API.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RateLimit {
+    /// Number of requests that can still be sent before the limit resets.
+    pub remaining: u32,
+
+    /// Time at which the limit resets.
+    pub reset_at: DateTime<Utc>,
+}
+
+/// Known budget of a category.
+#[derive(Debug)]
+enum Budget {
+    /// Limit reported by the API.
+    Reported(RateLimit),
+
+    /// The limit has reset, and a request is finding out the new budget until the specified time.
+    Probing(DateTime<Utc>),
+}
+
+/// Tracks the rate limits reported by the API for every category.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimits {
+    limits: Mutex<HashMap<RateLimitCategory, Budget>>,
+}
+
+impl RateLimits {
+    /// Gets the current limit of a category, unless unknown or already reset.
+    pub(crate) fn get(&self, category: RateLimitCategory) -> Option<RateLimit> {
+        let limits = self.limits.lock().unwrap();
+
+        match limits.get(&category) {
+            Some(Budget::Reported(limit)) if limit.reset_at > Utc::now() => Some(*limit),
+            _ => None,
+        }
+    }
+
+    /// Records the limit reported in the headers of a response.
+    pub(crate) fn update(&self, category: RateLimitCategory, headers: &HeaderMap) {
+        let remaining = headers
+            .get(REMAINING_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+
+        if let (Some(remaining), Some(reset_at)) = (remaining, reset_at(headers)) {
+            self.limits.lock().unwrap().insert(
+                category,
+                Budget::Reported(RateLimit {
+                    remaining,
+                    reset_at,
+                }),
+            );
+        }
+    }
+
+    /// Reserves a request of the category.
+    ///
+    /// Returns how long to wait before trying to reserve it again if the budget is exhausted.
+    ///
+    /// Once the limit resets, the new budget is unknown until a response reports it. Only one
+    /// request is let through to find it out, the others wait for its response.
+    pub(crate) fn reserve(&self, category: RateLimitCategory) -> Option<Duration> {
+        let mut limits = self.limits.lock().unwrap();
+        let budget = limits.get_mut(&category)?;
+        let now = Utc::now();
+
+        let wait_until = match budget {
+            Budget::Reported(limit) if limit.reset_at > now && limit.remaining > 0 => {
+                limit.remaining -= 1;
+                return None;
+            }
+            Budget::Reported(limit) if limit.reset_at > now => limit.reset_at,
+            Budget::Probing(until) if *until > now => *until,
+            _ => {
+                *budget = Budget::Probing(now + ChronoDuration::milliseconds(PROBE_TIMEOUT_MILLIS));
+                return None;
+            }
+        };
+
+        (wait_until - now).to_std().ok()
+    }
+}
+
+/// Reads the time at which the rate limit resets from the headers of a response.
+pub(crate) fn reset_at(headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let seconds = headers.get(RESET_HEADER)?.to_str().ok()?.parse().ok()?;
+
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rate_limit::{RateLimit, RateLimitCategory, RateLimits};
+    use chrono::{TimeZone, Utc};
+    use reqwest::header::HeaderMap;
+    use std::time::Duration;
+
+    fn headers(remaining: u32, reset_at: i64) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-RateLimit-Remaining", remaining.into());
+        headers.insert("X-RateLimit-Reset", reset_at.into());
+
+        headers
+    }
+
+    #[test]
+    fn category() {
+        assert_eq!(
+            RateLimitCategory::of("accounts/26598145/positions"),
+            RateLimitCategory::Account
+        );
+        assert_eq!(RateLimitCategory::of("time"), RateLimitCategory::Account);
+        assert_eq!(
+            RateLimitCategory::of("markets/quotes"),
+            RateLimitCategory::MarketData
+        );
+        assert_eq!(
+            RateLimitCategory::of("symbols/search"),
+            RateLimitCategory::MarketData
+        );
+    }
+
+    #[test]
+    fn reserve() {
+        let limits = RateLimits::default();
+        let reset_at = Utc.timestamp_opt(Utc::now().timestamp() + 10, 0).unwrap();
+
+        assert_eq!(limits.reserve(RateLimitCategory::Account), None);
+
+        limits.update(
+            RateLimitCategory::Account,
+            &headers(1, reset_at.timestamp()),
+        );
+        assert_eq!(limits.get(RateLimitCategory::MarketData), None);
+        assert_eq!(limits.reserve(RateLimitCategory::Account), None);
+        assert_eq!(
+            limits.get(RateLimitCategory::Account),
+            Some(RateLimit {
+                remaining: 0,
+                reset_at
+            })
+        );
+
+        let wait = limits.reserve(RateLimitCategory::Account).unwrap();
+        assert!(wait <= Duration::from_secs(10));
+        assert!(wait > Duration::from_secs(8));
+
+        limits.update(
+            RateLimitCategory::Account,
+            &headers(0, Utc::now().timestamp() - 1),
+        );
+        assert_eq!(limits.get(RateLimitCategory::Account), None);
+        assert_eq!(limits.reserve(RateLimitCategory::Account), None);
+
+        // Only the first request after a reset goes through until the new budget is reported.
+        let wait = limits.reserve(RateLimitCategory::Account).unwrap();
+        assert!(wait <= Duration::from_secs(1));
+        assert_eq!(limits.get(RateLimitCategory::Account), None);
+
+        limits.update(
+            RateLimitCategory::Account,
+            &headers(5, reset_at.timestamp()),
+        );
+        assert_eq!(limits.reserve(RateLimitCategory::Account), None);
+        assert_eq!(
+            limits
+                .get(RateLimitCategory::Account)
+                .map(|limit| limit.remaining),
+            Some(4)
+        );
+    }
+}
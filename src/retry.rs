@@ -0,0 +1,254 @@
+use crate::error::QuestradeError;
+use crate::random::random_duration;
+use chrono::Utc;
+use http::StatusCode;
+use std::cmp::{max, min};
+use std::time::Duration;
+
+/// Policy for retrying requests failing with transient errors.
+///
+/// Transport errors and responses with a retryable status (by default 429 and 5xx gateway and
+/// server errors) are retried, waiting longer after every attempt. When the API reports when its
+/// rate limit resets, the retry is delayed until then, unless the reset is further away than the
+/// maximum delay.
+///
+/// Only read-only requests (`get` requests and quote lookups) are retried, unless enabled with
+/// `with_non_idempotent_retries`. Requests changing state (Eg: cancelling an order) may have
+/// succeeded even though their response was lost.
+#[derive(Clone, PartialEq, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: Duration,
+    max_delay: Duration,
+    retryable_statuses: Vec<StatusCode>,
+    retry_non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// Creates a policy sending a request at most `max_attempts` times (including the first
+    /// attempt), with the default backoff and retryable statuses.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: Duration::from_millis(250),
+            max_delay: Duration::from_secs(60),
+            retryable_statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_non_idempotent: false,
+        }
+    }
+
+    /// Creates a policy that never retries.
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    /// Sets the delay before the first retry, doubled after every attempt up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets the maximum random delay added to every backoff.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the longest delay before a retry. Defaults to a minute.
+    ///
+    /// Rate limited requests aren't retried if the limit resets later than that.
+    pub fn with_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sets the response statuses that are retried.
+    pub fn with_retryable_statuses(
+        mut self,
+        statuses: impl IntoIterator<Item = StatusCode>,
+    ) -> Self {
+        self.retryable_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Sets whether requests that change state (Eg: placing or cancelling an order) are retried as
+    /// well.
+    pub fn with_non_idempotent_retries(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    /// Gets the delay before retrying a failed attempt, or `None` if it shouldn't be retried.
+    ///
+    /// `attempt` is the number of the attempt that failed, starting at 1.
+    pub(crate) fn retry_delay(
+        &self,
+        attempt: u32,
+        read_only: bool,
+        error: &QuestradeError,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !(read_only || self.retry_non_idempotent) {
+            return None;
+        }
+
+        let backoff = min(self.backoff(attempt), self.max_delay);
+
+        match error {
+            QuestradeError::TransportError(_) => Some(backoff),
+            QuestradeError::StatusError { status, .. }
+            | QuestradeError::ApiError { status, .. }
+                if self.retryable_statuses.contains(status) =>
+            {
+                Some(backoff)
+            }
+            QuestradeError::RateLimitError { reset_at, .. }
+                if self
+                    .retryable_statuses
+                    .contains(&StatusCode::TOO_MANY_REQUESTS) =>
+            {
+                let until_reset = reset_at
+                    .and_then(|reset_at| (reset_at - Utc::now()).to_std().ok())
+                    .unwrap_or_default();
+
+                if until_reset > self.max_delay {
+                    return None;
+                }
+
+                Some(min(max(backoff, until_reset), self.max_delay))
+            }
+            _ => None,
+        }
+    }
+
+    /// Backoff after the specified attempt, including jitter.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff);
+
+        min(backoff, self.max_backoff) + random_duration(self.jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Sends a request at most 3 times.
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::QuestradeError;
+    use crate::retry::RetryPolicy;
+    use chrono::{Duration as ChronoDuration, Utc};
+    use http::StatusCode;
+    use std::time::Duration;
+
+    fn status_error(status: StatusCode) -> QuestradeError {
+        QuestradeError::StatusError {
+            endpoint: "time".to_string(),
+            status,
+            body: String::new(),
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new(5)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .with_jitter(Duration::from_secs(0));
+        let error = status_error(StatusCode::SERVICE_UNAVAILABLE);
+
+        assert_eq!(
+            policy.retry_delay(1, true, &error),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.retry_delay(2, true, &error),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            policy.retry_delay(3, true, &error),
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(policy.retry_delay(5, true, &error), None);
+        assert_eq!(RetryPolicy::none().retry_delay(1, true, &error), None);
+    }
+
+    #[test]
+    fn retryable() {
+        let policy = RetryPolicy::default().with_jitter(Duration::from_secs(0));
+
+        assert!(policy
+            .retry_delay(1, true, &status_error(StatusCode::BAD_GATEWAY))
+            .is_some());
+        assert!(policy
+            .retry_delay(1, true, &status_error(StatusCode::BAD_REQUEST))
+            .is_none());
+        assert!(policy
+            .retry_delay(1, false, &status_error(StatusCode::BAD_GATEWAY))
+            .is_none());
+        assert!(policy
+            .clone()
+            .with_non_idempotent_retries(true)
+            .retry_delay(1, false, &status_error(StatusCode::BAD_GATEWAY))
+            .is_some());
+        assert!(policy
+            .retry_delay(1, true, &QuestradeError::MissingTokenError)
+            .is_none());
+
+        let only_unavailable =
+            RetryPolicy::default().with_retryable_statuses(vec![StatusCode::SERVICE_UNAVAILABLE]);
+        assert!(only_unavailable
+            .retry_delay(1, true, &status_error(StatusCode::BAD_GATEWAY))
+            .is_none());
+    }
+
+    #[test]
+    fn respects_reset() {
+        let policy = RetryPolicy::default().with_jitter(Duration::from_secs(0));
+        let error = QuestradeError::RateLimitError {
+            endpoint: "time".to_string(),
+            reset_at: Some(Utc::now() + ChronoDuration::seconds(10)),
+        };
+
+        let delay = policy.retry_delay(1, true, &error).unwrap();
+        assert!(delay > Duration::from_secs(9));
+        assert!(delay <= Duration::from_secs(10));
+
+        let error = QuestradeError::RateLimitError {
+            endpoint: "time".to_string(),
+            reset_at: None,
+        };
+        assert_eq!(
+            policy.retry_delay(1, true, &error),
+            Some(Duration::from_millis(500))
+        );
+
+        let error = QuestradeError::RateLimitError {
+            endpoint: "time".to_string(),
+            reset_at: Some(Utc::now() + ChronoDuration::days(365)),
+        };
+        assert_eq!(policy.retry_delay(1, true, &error), None);
+        assert_eq!(
+            policy
+                .with_max_delay(Duration::from_millis(100))
+                .retry_delay(1, true, &status_error(StatusCode::BAD_GATEWAY)),
+            Some(Duration::from_millis(100))
+        );
+    }
+}
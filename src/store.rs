@@ -0,0 +1,220 @@
+use crate::auth::AuthenticationInfo;
+use std::fs;
+use std::io;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::task::spawn_blocking;
+
+/// Persistent storage for authentication info.
+///
+/// Questrade refresh tokens can only be used once, every authentication returns a new one. The
+/// client saves the new info to the store as soon as it's received, so it survives a restart.
+///
+/// The client calls the store on the blocking thread pool, so implementations are free to do
+/// blocking I/O.
+pub trait TokenStore: Send + Sync {
+    /// Loads the last saved authentication info (if any).
+    fn load(&self) -> io::Result<Option<AuthenticationInfo>>;
+
+    /// Saves authentication info, replacing the previous one.
+    fn save(&self, auth_info: &AuthenticationInfo) -> io::Result<()>;
+}
+
+impl<S: TokenStore + ?Sized> TokenStore for Arc<S> {
+    fn load(&self) -> io::Result<Option<AuthenticationInfo>> {
+        (**self).load()
+    }
+
+    fn save(&self, auth_info: &AuthenticationInfo) -> io::Result<()> {
+        (**self).save(auth_info)
+    }
+}
+
+/// Token store backed by a JSON file.
+///
+/// Info is written to a temporary file which is then renamed over the original, so the file
+/// never contains a partially written token. On unix the file is only readable by its owner.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    /// Creates a store persisting authentication info to the specified file.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTokenStore { path: path.into() }
+    }
+
+    /// Path of the temporary file written while saving.
+    fn temp_path(&self) -> PathBuf {
+        let mut file_name = self.path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".tmp");
+
+        self.path.with_file_name(file_name)
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self) -> io::Result<Option<AuthenticationInfo>> {
+        match fs::read(&self.path) {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self, auth_info: &AuthenticationInfo) -> io::Result<()> {
+        let temp_path = self.temp_path();
+
+        // A leftover temporary file would keep its permissions, start from a new one.
+        match fs::remove_file(&temp_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(&temp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(auth_info)?)?;
+        file.sync_all()?;
+
+        fs::rename(&temp_path, &self.path)?;
+        sync_parent(&self.path)
+    }
+}
+
+/// Flushes the directory entry of a renamed file, so the rename survives a crash.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::File::open(parent)?.sync_all(),
+        _ => fs::File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories can't be opened as files on other platforms, the rename is left to the OS.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Loads authentication info from a store on the blocking thread pool.
+pub(crate) async fn load(store: Arc<dyn TokenStore>) -> io::Result<Option<AuthenticationInfo>> {
+    spawn_blocking(move || store.load())
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Saves authentication info to a store on the blocking thread pool.
+pub(crate) async fn save(
+    store: Arc<dyn TokenStore>,
+    auth_info: AuthenticationInfo,
+) -> io::Result<()> {
+    spawn_blocking(move || store.save(&auth_info))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Token store kept in memory, mostly useful for tests.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    auth_info: Mutex<Option<AuthenticationInfo>>,
+}
+
+impl MemoryTokenStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a store holding the specified info.
+    pub fn with_auth_info(auth_info: AuthenticationInfo) -> Self {
+        MemoryTokenStore {
+            auth_info: Mutex::new(Some(auth_info)),
+        }
+    }
+}
+
+impl TokenStore for MemoryTokenStore {
+    fn load(&self) -> io::Result<Option<AuthenticationInfo>> {
+        Ok(self.auth_info.lock().unwrap().clone())
+    }
+
+    fn save(&self, auth_info: &AuthenticationInfo) -> io::Result<()> {
+        self.auth_info.lock().unwrap().replace(auth_info.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::AuthenticationInfo;
+    use crate::environment::Environment;
+    use crate::store::{FileTokenStore, MemoryTokenStore, TokenStore};
+    use chrono::{TimeZone, Utc};
+    use std::env::temp_dir;
+    use std::error::Error;
+    use std::fs;
+
+    fn auth_info(refresh_token: &str) -> AuthenticationInfo {
+        AuthenticationInfo {
+            refresh_token: refresh_token.to_string(),
+            access_token: "access-token".to_string(),
+            expires_at: Utc.with_ymd_and_hms(2020, 11, 1, 15, 30, 0).unwrap(),
+            api_server: "https://api01.iq.questrade.com".to_string(),
+            environment: Environment::Practice,
+        }
+    }
+
+    #[test]
+    fn file_token_store() -> Result<(), Box<dyn Error>> {
+        let path = temp_dir().join(format!("questrade-token-{}.json", std::process::id()));
+        let store = FileTokenStore::new(&path);
+
+        assert_eq!(store.load()?, None);
+
+        store.save(&auth_info("first-token"))?;
+        assert_eq!(store.load()?, Some(auth_info("first-token")));
+
+        let second = auth_info("second-token");
+        store.save(&second)?;
+        assert_eq!(store.load()?, Some(second));
+        assert!(!store.temp_path().exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::write(&path, "not json")?;
+        assert!(store.load().is_err());
+
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn memory_token_store() -> Result<(), Box<dyn Error>> {
+        let store = MemoryTokenStore::new();
+        assert_eq!(store.load()?, None);
+
+        store.save(&auth_info("token"))?;
+        assert_eq!(store.load()?, Some(auth_info("token")));
+
+        let other = auth_info("other");
+        assert_eq!(
+            MemoryTokenStore::with_auth_info(other.clone()).load()?,
+            Some(other)
+        );
+
+        Ok(())
+    }
+}